@@ -0,0 +1,94 @@
+//! The target coordinate reference system for the pseudo-georeferenced
+//! world extent. Defaults to EPSG:3857, but can be swapped out via the
+//! `--crs`, `--crs-wkt` and `--bbox` CLI flags.
+
+use std::old_io::File;
+
+static WKT_3857: &'static str = include_str!("3857.esriwkt");
+static WKT_4326: &'static str = include_str!("4326.esriwkt");
+
+static BBOX_3857: [f64; 4] = [-20026376.39, -20048966.10, 20026376.39, 20048966.10];
+static BBOX_4326: [f64; 4] = [-180.0, -90.0, 180.0, 90.0];
+
+/// a validated geographic coordinate, in degrees.
+pub struct Coord {
+    pub lat: f64,
+    pub lon: f64
+}
+
+impl Coord {
+    pub fn new(lat: f64, lon: f64) -> Coord {
+        assert!(lat >= -90.0 && lat <= 90.0, "latitude {} out of range -90..=90", lat);
+        assert!(lon >= -180.0 && lon <= 180.0, "longitude {} out of range -180..=180", lon);
+        Coord { lat: lat, lon: lon }
+    }
+}
+
+/// a target CRS: its WKT definition plus the valid world extent coordinates
+/// are expressed in. `epsg`/`geographic` let consumers (like the GeoTIFF
+/// writer) emit proper numeric GeoKeys instead of only a WKT citation.
+pub struct Crs {
+    pub wkt: String,
+    pub bbox: [f64; 4],
+    pub epsg: Option<u32>,
+    pub geographic: bool
+}
+
+impl Crs {
+    pub fn epsg_3857() -> Crs {
+        Crs { wkt: WKT_3857.to_string(), bbox: BBOX_3857, epsg: Some(3857), geographic: false }
+    }
+
+    pub fn epsg_4326() -> Crs {
+        Crs { wkt: WKT_4326.to_string(), bbox: BBOX_4326, epsg: Some(4326), geographic: true }
+    }
+
+    /// look up one of the CRSes built into the tool by EPSG code.
+    pub fn from_epsg(code: u32) -> Option<Crs> {
+        match code {
+            3857 => Some(Crs::epsg_3857()),
+            4326 => Some(Crs::epsg_4326()),
+            _ => None
+        }
+    }
+
+    /// read a WKT definition from a `.prj` file. The world extent still
+    /// needs to be supplied via `--bbox` since it can't be derived from
+    /// the WKT alone; the EPSG code is left unknown, and whether it's a
+    /// geographic or projected CRS is inferred from the WKT's outermost
+    /// keyword (`GEOGCS`/`PROJCS`).
+    pub fn from_wkt_file(path: &Path, bbox: [f64; 4]) -> ::std::old_io::IoResult<Crs> {
+        let mut file = try!(File::open(path));
+        let wkt = try!(file.read_to_string());
+        let geographic = wkt_is_geographic(wkt.as_slice());
+        Ok(Crs { wkt: wkt, bbox: bbox, epsg: None, geographic: geographic })
+    }
+
+    pub fn with_bbox(mut self, bbox: [f64; 4]) -> Crs {
+        self.bbox = bbox;
+        self
+    }
+
+    /// whether this CRS is the Web Mercator projection the GPS, tile and
+    /// GeoJSON code paths assume their coordinates are already in.
+    pub fn is_web_mercator(&self) -> bool {
+        self.epsg == Some(3857)
+    }
+}
+
+/// infer whether a WKT definition describes a geographic (`GEOGCS`) or
+/// projected (`PROJCS`) CRS from its outermost keyword. Defaults to
+/// projected (`false`) if neither is found.
+fn wkt_is_geographic(wkt: &str) -> bool {
+    let trimmed = wkt.trim_start();
+    trimmed.starts_with("GEOGCS")
+}
+
+/// parse a `--bbox minx,miny,maxx,maxy` CLI argument.
+pub fn parse_bbox(s: &str) -> Option<[f64; 4]> {
+    let parts: Vec<f64> = s.split(',').filter_map(|p| p.parse().ok()).collect();
+    if parts.len() != 4 {
+        return None;
+    }
+    Some([parts[0], parts[1], parts[2], parts[3]])
+}
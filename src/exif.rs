@@ -0,0 +1,264 @@
+//! Minimal EXIF reader, just enough to pull GPS tags out of JPEG/TIFF
+//! containers. Mirrors the approach exif-rs uses: find the APP1 segment
+//! (JPEG) or take the whole file (TIFF), then walk the TIFF IFD chain
+//! looking for the GPS sub-IFD.
+
+use std::old_io::{File, IoError};
+
+#[derive(Debug)]
+pub enum ExifError {
+    Io(IoError),
+    NotFound
+}
+
+impl std::error::FromError<IoError> for ExifError {
+    fn from_error(err: IoError) -> ExifError {
+        ExifError::Io(err)
+    }
+}
+
+/// GPS information extracted from EXIF tags.
+#[derive(Debug)]
+pub struct GpsInfo {
+    pub lat: f64,
+    pub lon: f64,
+    pub altitude: Option<f64>,
+    pub img_direction: Option<f64>
+}
+
+struct Rational {
+    num: u32,
+    den: u32
+}
+
+impl Rational {
+    fn as_f64(&self) -> f64 {
+        if self.den == 0 {
+            0.0
+        } else {
+            self.num as f64 / self.den as f64
+        }
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize, big_endian: bool) -> u16 {
+    if big_endian {
+        ((buf[offset] as u16) << 8) | (buf[offset + 1] as u16)
+    } else {
+        ((buf[offset + 1] as u16) << 8) | (buf[offset] as u16)
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize, big_endian: bool) -> u32 {
+    if big_endian {
+        ((buf[offset] as u32) << 24) | ((buf[offset + 1] as u32) << 16)
+            | ((buf[offset + 2] as u32) << 8) | (buf[offset + 3] as u32)
+    } else {
+        ((buf[offset + 3] as u32) << 24) | ((buf[offset + 2] as u32) << 16)
+            | ((buf[offset + 1] as u32) << 8) | (buf[offset] as u32)
+    }
+}
+
+fn read_rational(buf: &[u8], offset: usize, big_endian: bool) -> Rational {
+    Rational {
+        num: read_u32(buf, offset, big_endian),
+        den: read_u32(buf, offset + 4, big_endian)
+    }
+}
+
+/// byte-typed IFD values (count 1) are stored inline in the entry's
+/// value/offset field rather than pointed to, in the first byte for the
+/// prevailing byte order.
+fn inline_byte(value_offset: u32, big_endian: bool) -> u8 {
+    if big_endian {
+        (value_offset >> 24) as u8
+    } else {
+        (value_offset & 0xFF) as u8
+    }
+}
+
+fn dms_to_decimal(buf: &[u8], value_offset: usize, big_endian: bool) -> f64 {
+    let degrees = read_rational(buf, value_offset, big_endian);
+    let minutes = read_rational(buf, value_offset + 8, big_endian);
+    let seconds = read_rational(buf, value_offset + 16, big_endian);
+    degrees.as_f64() + (minutes.as_f64() / 60.0) + (seconds.as_f64() / 3600.0)
+}
+
+/// locate the EXIF/TIFF blob inside a JPEG's APP1 segment, or treat the
+/// whole buffer as a TIFF if it already starts with a byte-order marker.
+fn find_tiff_start(buf: &[u8]) -> Option<usize> {
+    if buf.len() > 4 && (&buf[0..2] == b"II" || &buf[0..2] == b"MM") {
+        return Some(0);
+    }
+
+    if buf.len() < 4 || buf[0] != 0xFF || buf[1] != 0xD8 {
+        return None;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= buf.len() {
+        if buf[pos] != 0xFF {
+            break;
+        }
+        let marker = buf[pos + 1];
+        let seg_len = read_u16(buf, pos + 2, true) as usize;
+        if marker == 0xE1 && pos + 4 + 6 <= buf.len() && &buf[pos + 4..pos + 10] == b"Exif\0\0" {
+            return Some(pos + 10);
+        }
+        if marker == 0xDA {
+            break; // start of scan, no more APPn segments follow
+        }
+        pos += 2 + seg_len;
+    }
+    None
+}
+
+fn ifd_entries(buf: &[u8], tiff_start: usize, ifd_offset: u32, big_endian: bool)
+        -> Vec<(u16, u16, u32, u32)> {
+    let mut entries = vec!();
+    let offset = tiff_start + ifd_offset as usize;
+    if offset + 2 > buf.len() {
+        return entries;
+    }
+    let count = read_u16(buf, offset, big_endian) as usize;
+    for i in 0..count {
+        let entry_offset = offset + 2 + (i * 12);
+        if entry_offset + 12 > buf.len() {
+            break;
+        }
+        let tag = read_u16(buf, entry_offset, big_endian);
+        let format = read_u16(buf, entry_offset + 2, big_endian);
+        let components = read_u32(buf, entry_offset + 4, big_endian);
+        let value_offset = read_u32(buf, entry_offset + 8, big_endian);
+        entries.push((tag, format, components, value_offset));
+    }
+    entries
+}
+
+/// read the GPS tags (if any) out of a JPEG or TIFF file.
+pub fn read_gps(imagepath: &Path) -> Result<GpsInfo, ExifError> {
+    let mut file = try!(File::open(imagepath));
+    let buf = try!(file.read_to_end());
+
+    let tiff_start = match find_tiff_start(buf.as_slice()) {
+        Some(pos) => pos,
+        None => return Err(ExifError::NotFound)
+    };
+    if tiff_start + 8 > buf.len() {
+        return Err(ExifError::NotFound);
+    }
+
+    let big_endian = &buf[tiff_start..tiff_start + 2] == b"MM";
+    let ifd0_offset = read_u32(buf.as_slice(), tiff_start + 4, big_endian);
+
+    // find the GPS IFD pointer (tag 0x8825) in IFD0
+    let mut gps_ifd_offset: Option<u32> = None;
+    for (tag, _format, _components, value_offset) in ifd_entries(buf.as_slice(), tiff_start, ifd0_offset, big_endian) {
+        if tag == 0x8825 {
+            gps_ifd_offset = Some(value_offset);
+        }
+    }
+
+    let gps_offset = match gps_ifd_offset {
+        Some(o) => o,
+        None => return Err(ExifError::NotFound)
+    };
+
+    let mut lat: Option<f64> = None;
+    let mut lon: Option<f64> = None;
+    let mut lat_ref: Option<u8> = None;
+    let mut lon_ref: Option<u8> = None;
+    let mut altitude: Option<f64> = None;
+    let mut altitude_ref: Option<u8> = None;
+    let mut img_direction: Option<f64> = None;
+
+    for (tag, _format, _components, value_offset) in ifd_entries(buf.as_slice(), tiff_start, gps_offset, big_endian) {
+        let abs_offset = tiff_start + value_offset as usize;
+        match tag {
+            0x0001 => lat_ref = Some(inline_byte(value_offset, big_endian)), // GPSLatitudeRef
+            0x0002 => if abs_offset + 24 <= buf.len() { lat = Some(dms_to_decimal(buf.as_slice(), abs_offset, big_endian)); },
+            0x0003 => lon_ref = Some(inline_byte(value_offset, big_endian)), // GPSLongitudeRef
+            0x0004 => if abs_offset + 24 <= buf.len() { lon = Some(dms_to_decimal(buf.as_slice(), abs_offset, big_endian)); },
+            0x0005 => altitude_ref = Some(inline_byte(value_offset, big_endian)), // GPSAltitudeRef
+            0x0006 => if abs_offset + 8 <= buf.len() { altitude = Some(read_rational(buf.as_slice(), abs_offset, big_endian).as_f64()); },
+            0x0011 => if abs_offset + 8 <= buf.len() { img_direction = Some(read_rational(buf.as_slice(), abs_offset, big_endian).as_f64()); },
+            _ => {}
+        }
+    }
+
+    let (mut lat_val, mut lon_val) = match (lat, lon) {
+        (Some(la), Some(lo)) => (la, lo),
+        _ => return Err(ExifError::NotFound)
+    };
+
+    if lat_ref == Some(b'S') {
+        lat_val = -lat_val;
+    }
+    if lon_ref == Some(b'W') {
+        lon_val = -lon_val;
+    }
+
+    let altitude_val = match (altitude, altitude_ref) {
+        (Some(a), Some(1)) => Some(-a),
+        (Some(a), _) => Some(a),
+        (None, _) => None
+    };
+
+    Ok(GpsInfo {
+        lat: lat_val,
+        lon: lon_val,
+        altitude: altitude_val,
+        img_direction: img_direction
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dms_to_decimal, inline_byte};
+
+    /// build a little-endian buffer holding three 8-byte rationals
+    /// (degrees, minutes, seconds) back to back, as `dms_to_decimal` expects.
+    fn dms_buf(degrees: (u32, u32), minutes: (u32, u32), seconds: (u32, u32)) -> Vec<u8> {
+        let mut buf = vec!();
+        for &(num, den) in &[degrees, minutes, seconds] {
+            buf.push_all(&[
+                (num & 0xFF) as u8, ((num >> 8) & 0xFF) as u8,
+                ((num >> 16) & 0xFF) as u8, ((num >> 24) & 0xFF) as u8,
+                (den & 0xFF) as u8, ((den >> 8) & 0xFF) as u8,
+                ((den >> 16) & 0xFF) as u8, ((den >> 24) & 0xFF) as u8
+            ]);
+        }
+        buf
+    }
+
+    #[test]
+    fn dms_to_decimal_converts_known_coordinate() {
+        // 40 deg, 26 min, 46.0 sec -> 40.446111...
+        let buf = dms_buf((40, 1), (26, 1), (460, 10));
+        let decimal = dms_to_decimal(buf.as_slice(), 0, false);
+        assert!((decimal - 40.446111).abs() < 1e-5);
+    }
+
+    #[test]
+    fn dms_to_decimal_handles_big_endian() {
+        let mut buf = dms_buf((40, 1), (26, 1), (460, 10));
+        // re-encode the same values as big-endian
+        buf.clear();
+        for &(num, den) in &[(40u32, 1u32), (26, 1), (460, 10)] {
+            buf.push_all(&[
+                ((num >> 24) & 0xFF) as u8, ((num >> 16) & 0xFF) as u8,
+                ((num >> 8) & 0xFF) as u8, (num & 0xFF) as u8,
+                ((den >> 24) & 0xFF) as u8, ((den >> 16) & 0xFF) as u8,
+                ((den >> 8) & 0xFF) as u8, (den & 0xFF) as u8
+            ]);
+        }
+        let decimal = dms_to_decimal(buf.as_slice(), 0, true);
+        assert!((decimal - 40.446111).abs() < 1e-5);
+    }
+
+    #[test]
+    fn inline_byte_reads_first_byte_for_prevailing_endianness() {
+        assert_eq!(inline_byte(0x53000000, true), b'S');
+        assert_eq!(inline_byte(0x00000053, false), b'S');
+    }
+}
@@ -0,0 +1,58 @@
+//! Writes a GeoJSON `FeatureCollection` of image footprints, as an
+//! alternative to the ad-hoc JSON produced by `-j` that GIS tools can
+//! consume directly.
+
+use rustc_serialize::json::Json;
+
+use webmercator::merc_to_lonlat;
+
+/// one image's footprint, in EPSG:3857, plus the metadata that goes into
+/// the feature's properties.
+pub struct Footprint {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub minx: f64,
+    pub miny: f64,
+    pub maxx: f64,
+    pub maxy: f64
+}
+
+fn json_string_or_null(value: &Option<String>) -> String {
+    match *value {
+        // reuse the same JSON encoder `-j` relies on, rather than Rust's
+        // Debug escaping (which emits `\u{7f}`-style escapes that aren't
+        // valid JSON for control bytes).
+        Some(ref v) => Json::String(v.clone()).to_string(),
+        None => "null".to_string()
+    }
+}
+
+fn feature(fp: &Footprint) -> String {
+    let (lon_min, lat_min) = merc_to_lonlat(fp.minx, fp.miny);
+    let (lon_max, lat_max) = merc_to_lonlat(fp.maxx, fp.maxy);
+
+    let ring = format!(
+        "[[{},{}],[{},{}],[{},{}],[{},{}],[{},{}]]",
+        lon_min, lat_min,
+        lon_max, lat_min,
+        lon_max, lat_max,
+        lon_min, lat_max,
+        lon_min, lat_min);
+
+    format!(
+        "{{\"type\":\"Feature\",\"geometry\":{{\"type\":\"Polygon\",\"coordinates\":[{}]}},\"properties\":{{\"name\":{},\"filename\":{},\"width\":{},\"height\":{}}}}}",
+        ring,
+        json_string_or_null(&fp.name),
+        json_string_or_null(&fp.filename),
+        fp.width,
+        fp.height)
+}
+
+/// render `footprints` as a GeoJSON `FeatureCollection` string, reprojected
+/// from EPSG:3857 back to WGS84 lon/lat.
+pub fn to_feature_collection(footprints: &[Footprint]) -> String {
+    let features: Vec<String> = footprints.iter().map(feature).collect();
+    format!("{{\"type\":\"FeatureCollection\",\"features\":[{}]}}", features.connect(","))
+}
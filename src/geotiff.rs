@@ -0,0 +1,217 @@
+//! Writes a self-describing GeoTIFF instead of the `.wld`/`.prj` sidecar
+//! files: `ModelPixelScaleTag` (33550) and `ModelTiepointTag` (33922) carry
+//! the same values as the world file, and `GeoKeyDirectoryTag` (34735) /
+//! `GeoAsciiParamsTag` (34737) carry `GTModelTypeGeoKey`, the matching
+//! `GeographicTypeGeoKey`/`ProjectedCSTypeGeoKey` (when the EPSG code is
+//! known) and a `GTCitationGeoKey` with the full CRS WKT.
+
+use std::old_io::{File, IoError};
+
+use image::{DynamicImage, GenericImage};
+
+use crs::Crs;
+
+#[derive(Debug)]
+pub enum GeoTiffError {
+    Io(IoError)
+}
+
+impl ::std::error::FromError<IoError> for GeoTiffError {
+    fn from_error(err: IoError) -> GeoTiffError {
+        GeoTiffError::Io(err)
+    }
+}
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_ROWS_PER_STRIP: u16 = 278;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_PLANAR_CONFIGURATION: u16 = 284;
+const TAG_MODEL_PIXEL_SCALE: u16 = 33550;
+const TAG_MODEL_TIEPOINT: u16 = 33922;
+const TAG_GEO_KEY_DIRECTORY: u16 = 34735;
+const TAG_GEO_ASCII_PARAMS: u16 = 34737;
+
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_ASCII: u16 = 2;
+const TYPE_DOUBLE: u16 = 12;
+
+const GEOKEY_GT_MODEL_TYPE: u16 = 1024;
+const GEOKEY_GT_CITATION: u16 = 1026;
+const GEOKEY_GEOGRAPHIC_TYPE: u16 = 2048;
+const GEOKEY_PROJECTED_CS_TYPE: u16 = 3072;
+
+const MODEL_TYPE_PROJECTED: u16 = 1;
+const MODEL_TYPE_GEOGRAPHIC: u16 = 2;
+
+/// one not-yet-placed IFD entry: its trailing value bytes get appended to
+/// the "extra data" area and the entry rewritten with the resulting offset
+/// once every entry's size is known.
+struct PendingEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    inline_value: u32,
+    extra_bytes: Vec<u8>
+}
+
+fn push_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.push((v & 0xFF) as u8);
+    buf.push((v >> 8) as u8);
+}
+
+fn push_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.push((v & 0xFF) as u8);
+    buf.push(((v >> 8) & 0xFF) as u8);
+    buf.push(((v >> 16) & 0xFF) as u8);
+    buf.push(((v >> 24) & 0xFF) as u8);
+}
+
+fn push_f64(buf: &mut Vec<u8>, v: f64) {
+    let bits = unsafe { ::std::mem::transmute::<f64, u64>(v) };
+    for i in 0..8 {
+        buf.push(((bits >> (i * 8)) & 0xFF) as u8);
+    }
+}
+
+fn entry_with_offset(entry: &PendingEntry, offset: u32) -> PendingEntry {
+    PendingEntry {
+        tag: entry.tag,
+        field_type: entry.field_type,
+        count: entry.count,
+        inline_value: offset,
+        extra_bytes: vec!()
+    }
+}
+
+/// write a TIFF with embedded GeoTIFF tags for `img`, georeferenced by
+/// `pixel_size_x/y` (map units/pixel, matching `RefBox::world_file_values`)
+/// and the upper-left corner `(ul_x, ul_y)`, tagged with `crs`.
+pub fn write_geotiff(img: &DynamicImage, pixel_size_x: f64, pixel_size_y: f64,
+                      ul_x: f64, ul_y: f64, crs: &Crs, outpath: &Path) -> Result<(), GeoTiffError> {
+    let rgb = img.to_rgb();
+    let (width, height) = (rgb.width(), rgb.height());
+    let pixel_data = rgb.into_vec();
+
+    // GeoAsciiParamsTag values are '|'-delimited and the last one ends in '|'
+    let ascii_params = format!("{}|", crs.wkt);
+
+    let model_type = if crs.geographic { MODEL_TYPE_GEOGRAPHIC } else { MODEL_TYPE_PROJECTED };
+
+    // GTModelTypeGeoKey and a GeographicTypeGeoKey/ProjectedCSTypeGeoKey (when
+    // the EPSG code is known) let readers resolve an actual spatial reference;
+    // GTCitationGeoKey additionally carries the full WKT for informational use.
+    let mut geokey_entries: Vec<(u16, u16, u16, u16)> = vec!(
+        (GEOKEY_GT_MODEL_TYPE, 0, 1, model_type)
+    );
+    if let Some(epsg) = crs.epsg {
+        let cs_type_key = if crs.geographic { GEOKEY_GEOGRAPHIC_TYPE } else { GEOKEY_PROJECTED_CS_TYPE };
+        geokey_entries.push((cs_type_key, 0, 1, epsg as u16));
+    }
+    geokey_entries.push((GEOKEY_GT_CITATION, TAG_GEO_ASCII_PARAMS, ascii_params.len() as u16, 0));
+
+    let mut geokeys: Vec<u8> = vec!();
+    push_u16(&mut geokeys, 1); // KeyDirectoryVersion
+    push_u16(&mut geokeys, 1); // KeyRevision
+    push_u16(&mut geokeys, 0); // MinorRevision
+    push_u16(&mut geokeys, geokey_entries.len() as u16); // NumberOfKeys
+    for &(key_id, tag_location, count, value) in geokey_entries.iter() {
+        push_u16(&mut geokeys, key_id);
+        push_u16(&mut geokeys, tag_location);
+        push_u16(&mut geokeys, count);
+        push_u16(&mut geokeys, value);
+    }
+
+    let mut pixel_scale: Vec<u8> = vec!();
+    push_f64(&mut pixel_scale, pixel_size_x);
+    push_f64(&mut pixel_scale, pixel_size_y.abs());
+    push_f64(&mut pixel_scale, 0.0);
+
+    let mut tiepoint: Vec<u8> = vec!();
+    push_f64(&mut tiepoint, 0.0);
+    push_f64(&mut tiepoint, 0.0);
+    push_f64(&mut tiepoint, 0.0);
+    push_f64(&mut tiepoint, ul_x);
+    push_f64(&mut tiepoint, ul_y);
+    push_f64(&mut tiepoint, 0.0);
+
+    let bits_per_sample: Vec<u8> = { let mut b = vec!(); push_u16(&mut b, 8); push_u16(&mut b, 8); push_u16(&mut b, 8); b };
+
+    let mut entries: Vec<PendingEntry> = vec!(
+        PendingEntry { tag: TAG_IMAGE_WIDTH, field_type: TYPE_LONG, count: 1, inline_value: width, extra_bytes: vec!() },
+        PendingEntry { tag: TAG_IMAGE_LENGTH, field_type: TYPE_LONG, count: 1, inline_value: height, extra_bytes: vec!() },
+        PendingEntry { tag: TAG_BITS_PER_SAMPLE, field_type: TYPE_SHORT, count: 3, inline_value: 0, extra_bytes: bits_per_sample },
+        PendingEntry { tag: TAG_COMPRESSION, field_type: TYPE_SHORT, count: 1, inline_value: 1, extra_bytes: vec!() },
+        PendingEntry { tag: TAG_PHOTOMETRIC_INTERPRETATION, field_type: TYPE_SHORT, count: 1, inline_value: 2, extra_bytes: vec!() },
+        // StripOffsets is patched in below once the pixel data's final offset is known
+        PendingEntry { tag: TAG_STRIP_OFFSETS, field_type: TYPE_LONG, count: 1, inline_value: 0, extra_bytes: vec!() },
+        PendingEntry { tag: TAG_SAMPLES_PER_PIXEL, field_type: TYPE_SHORT, count: 1, inline_value: 3, extra_bytes: vec!() },
+        PendingEntry { tag: TAG_ROWS_PER_STRIP, field_type: TYPE_LONG, count: 1, inline_value: height, extra_bytes: vec!() },
+        PendingEntry { tag: TAG_STRIP_BYTE_COUNTS, field_type: TYPE_LONG, count: 1, inline_value: pixel_data.len() as u32, extra_bytes: vec!() },
+        PendingEntry { tag: TAG_PLANAR_CONFIGURATION, field_type: TYPE_SHORT, count: 1, inline_value: 1, extra_bytes: vec!() },
+        PendingEntry { tag: TAG_MODEL_PIXEL_SCALE, field_type: TYPE_DOUBLE, count: 3, inline_value: 0, extra_bytes: pixel_scale },
+        PendingEntry { tag: TAG_MODEL_TIEPOINT, field_type: TYPE_DOUBLE, count: 6, inline_value: 0, extra_bytes: tiepoint },
+        PendingEntry { tag: TAG_GEO_KEY_DIRECTORY, field_type: TYPE_SHORT, count: (geokeys.len() / 2) as u32, inline_value: 0, extra_bytes: geokeys },
+        PendingEntry { tag: TAG_GEO_ASCII_PARAMS, field_type: TYPE_ASCII, count: ascii_params.len() as u32, inline_value: 0, extra_bytes: ascii_params.into_bytes() }
+    );
+    entries.sort_by(|a, b| a.tag.cmp(&b.tag));
+
+    // layout: 8-byte header, then the pixel data strip, then every entry's
+    // extra bytes (>4 bytes of value), then the IFD itself.
+    let header_size = 8u32;
+    let strip_offset = header_size;
+    let mut cursor = strip_offset + pixel_data.len() as u32;
+
+    let mut resolved: Vec<PendingEntry> = vec!();
+    for entry in entries.into_iter() {
+        if entry.tag == TAG_STRIP_OFFSETS {
+            resolved.push(entry_with_offset(&entry, strip_offset));
+        } else if entry.extra_bytes.is_empty() {
+            resolved.push(entry);
+        } else {
+            let offset = cursor;
+            cursor += entry.extra_bytes.len() as u32;
+            if cursor % 2 == 1 {
+                cursor += 1; // IFD entries must start on a word boundary
+            }
+            resolved.push(PendingEntry { tag: entry.tag, field_type: entry.field_type, count: entry.count, inline_value: offset, extra_bytes: entry.extra_bytes });
+        }
+    }
+
+    let ifd_offset = cursor;
+
+    let mut out: Vec<u8> = vec!();
+    push_u16(&mut out, 0x4949); // "II", little-endian
+    push_u16(&mut out, 42);
+    push_u32(&mut out, ifd_offset);
+
+    out.extend(pixel_data.into_iter());
+
+    for entry in resolved.iter() {
+        if !entry.extra_bytes.is_empty() {
+            out.extend(entry.extra_bytes.iter().cloned());
+            if out.len() % 2 == 1 {
+                out.push(0);
+            }
+        }
+    }
+
+    push_u16(&mut out, resolved.len() as u16);
+    for entry in resolved.iter() {
+        push_u16(&mut out, entry.tag);
+        push_u16(&mut out, entry.field_type);
+        push_u32(&mut out, entry.count);
+        push_u32(&mut out, entry.inline_value);
+    }
+    push_u32(&mut out, 0); // no further IFDs
+
+    let mut file = try!(File::create(outpath));
+    try!(file.write_all(out.as_slice()));
+    Ok(())
+}
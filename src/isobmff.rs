@@ -0,0 +1,275 @@
+//! Minimal ISOBMFF (ISO base media file format) box walker, enough to
+//! recover image dimensions from HEIC/HEIF/AVIF files without decoding
+//! any pixels. Mirrors the box path exif-rs's `isobmff.rs` uses: `ftyp`
+//! to sniff the brand, then `meta` -> `iprp` -> `ipco` -> `ispe`.
+
+use std::old_io::{File, IoError};
+
+/// how many leading bytes to read when sniffing for an ISOBMFF `ftyp` box,
+/// before committing to reading the whole file.
+const SNIFF_BYTES: usize = 512;
+
+#[derive(Debug)]
+pub enum IsobmffError {
+    Io(IoError),
+    NotIsobmff,
+    NoSpatialExtents
+}
+
+impl ::std::error::FromError<IoError> for IsobmffError {
+    fn from_error(err: IoError) -> IsobmffError {
+        IsobmffError::Io(err)
+    }
+}
+
+static RECOGNIZED_BRANDS: &'static [&'static str] = &["heic", "heix", "heim", "heis", "hevc", "mif1", "avif", "avis"];
+
+struct BoxHeader {
+    box_type: [u8; 4],
+    /// offset of the box's payload (after the header)
+    payload_start: usize,
+    /// offset just past the end of the box
+    end: usize
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    ((buf[offset] as u16) << 8) | (buf[offset + 1] as u16)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    ((buf[offset] as u32) << 24) | ((buf[offset + 1] as u32) << 16)
+        | ((buf[offset + 2] as u32) << 8) | (buf[offset + 3] as u32)
+}
+
+fn read_box_header(buf: &[u8], offset: usize) -> Option<BoxHeader> {
+    if offset + 8 > buf.len() {
+        return None;
+    }
+    let size32 = read_u32(buf, offset) as usize;
+    let mut box_type = [0u8; 4];
+    box_type.clone_from_slice(&buf[offset + 4..offset + 8]);
+
+    let (payload_start, size) = if size32 == 1 {
+        // 64-bit extended size follows the type
+        if offset + 16 > buf.len() {
+            return None;
+        }
+        let size64 = ((read_u32(buf, offset + 8) as u64) << 32) | (read_u32(buf, offset + 12) as u64);
+        (offset + 16, size64 as usize)
+    } else {
+        (offset + 8, size32)
+    };
+
+    if size == 0 || offset + size > buf.len() {
+        return None;
+    }
+
+    Some(BoxHeader { box_type: box_type, payload_start: payload_start, end: offset + size })
+}
+
+fn find_child_box(buf: &[u8], container_start: usize, container_end: usize, wanted: &[u8; 4]) -> Option<BoxHeader> {
+    let mut pos = container_start;
+    while pos < container_end {
+        let header = match read_box_header(buf, pos) {
+            Some(h) => h,
+            None => return None
+        };
+        if &header.box_type == wanted {
+            return Some(header);
+        }
+        pos = header.end;
+    }
+    None
+}
+
+fn child_boxes(buf: &[u8], container_start: usize, container_end: usize) -> Vec<BoxHeader> {
+    let mut children = vec!();
+    let mut pos = container_start;
+    while pos < container_end {
+        let header = match read_box_header(buf, pos) {
+            Some(h) => h,
+            None => break
+        };
+        pos = header.end;
+        children.push(header);
+    }
+    children
+}
+
+/// the item id of the `meta` box's primary image, from its `pitm` child.
+fn primary_item_id(buf: &[u8], meta_children_start: usize, meta_end: usize) -> Option<u32> {
+    let pitm = match find_child_box(buf, meta_children_start, meta_end, b"pitm") {
+        Some(h) => h,
+        None => return None
+    };
+    if pitm.payload_start >= buf.len() {
+        return None;
+    }
+    let version = buf[pitm.payload_start];
+    let id_offset = pitm.payload_start + 4;
+    if version == 0 {
+        if id_offset + 2 > buf.len() { return None; }
+        Some(read_u16(buf, id_offset) as u32)
+    } else {
+        if id_offset + 4 > buf.len() { return None; }
+        Some(read_u32(buf, id_offset))
+    }
+}
+
+/// the (1-based) `ipco` property indices associated with `item_id`, read
+/// from the `iprp` box's `ipma` child.
+fn item_property_indices(buf: &[u8], iprp_start: usize, iprp_end: usize, item_id: u32) -> Vec<u32> {
+    let ipma = match find_child_box(buf, iprp_start, iprp_end, b"ipma") {
+        Some(h) => h,
+        None => return vec!()
+    };
+    if ipma.payload_start + 4 > buf.len() {
+        return vec!();
+    }
+
+    let version = buf[ipma.payload_start];
+    let flags = read_u32(buf, ipma.payload_start) & 0x00FFFFFF;
+    let fifteen_bit_indices = flags & 1 == 1;
+
+    let mut pos = ipma.payload_start + 4;
+    if pos + 4 > ipma.end {
+        return vec!();
+    }
+    let entry_count = read_u32(buf, pos);
+    pos += 4;
+
+    for _ in 0..entry_count {
+        if pos + 2 > ipma.end {
+            break;
+        }
+        let entry_item_id = if version < 1 {
+            let id = read_u16(buf, pos) as u32;
+            pos += 2;
+            id
+        } else {
+            let id = read_u32(buf, pos);
+            pos += 4;
+            id
+        };
+
+        if pos >= ipma.end {
+            break;
+        }
+        let association_count = buf[pos] as usize;
+        pos += 1;
+
+        let mut indices = vec!();
+        for _ in 0..association_count {
+            if fifteen_bit_indices {
+                if pos + 2 > ipma.end { break; }
+                indices.push((read_u16(buf, pos) & 0x7FFF) as u32);
+                pos += 2;
+            } else {
+                if pos + 1 > ipma.end { break; }
+                indices.push((buf[pos] & 0x7F) as u32);
+                pos += 1;
+            }
+        }
+
+        if entry_item_id == item_id {
+            return indices;
+        }
+    }
+
+    vec!()
+}
+
+/// sniff whether `buf` starts with an ISOBMFF `ftyp` box carrying one of
+/// the HEIF/AVIF brands we understand.
+pub fn is_isobmff_image(buf: &[u8]) -> bool {
+    let header = match read_box_header(buf, 0) {
+        Some(h) => h,
+        None => return false
+    };
+    if &header.box_type != b"ftyp" {
+        return false;
+    }
+    if header.payload_start + 4 > buf.len() {
+        return false;
+    }
+
+    let major_brand = &buf[header.payload_start..header.payload_start + 4];
+    let mut pos = header.payload_start + 8; // skip major_brand + minor_version
+    let mut brands: Vec<&[u8]> = vec!(major_brand);
+    while pos + 4 <= header.end {
+        brands.push(&buf[pos..pos + 4]);
+        pos += 4;
+    }
+
+    brands.iter().any(|b| RECOGNIZED_BRANDS.iter().any(|rb| rb.as_bytes() == *b))
+}
+
+fn ispe_dimensions(buf: &[u8], ispe: &BoxHeader) -> Option<(u32, u32)> {
+    // ispe payload: 4 bytes version/flags, then width (u32), height (u32)
+    let width_offset = ispe.payload_start + 4;
+    if width_offset + 8 > buf.len() {
+        return None;
+    }
+    Some((read_u32(buf, width_offset), read_u32(buf, width_offset + 4)))
+}
+
+/// walk `meta` -> `iprp` -> `ipco` -> `ispe` to recover the primary image's
+/// pixel dimensions, resolving the primary item through `pitm`/`ipma`
+/// rather than assuming the first `ispe` belongs to it (HEIC files
+/// commonly carry a thumbnail's `ispe` alongside the primary image's).
+pub fn read_dimensions(imagepath: &Path) -> Result<(u32, u32), IsobmffError> {
+    let mut file = try!(File::open(imagepath));
+
+    let stat = try!(file.stat());
+    let sniff_len = if (stat.size as usize) < SNIFF_BYTES { stat.size as usize } else { SNIFF_BYTES };
+    let sniff_buf = try!(file.read_exact(sniff_len));
+
+    if !is_isobmff_image(sniff_buf.as_slice()) {
+        return Err(IsobmffError::NotIsobmff);
+    }
+
+    // confirmed ISOBMFF - read the rest of the file to walk the box tree
+    let mut buf = sniff_buf;
+    if sniff_len < stat.size as usize {
+        let rest = try!(file.read_to_end());
+        buf.extend(rest.into_iter());
+    }
+
+    let meta = match find_child_box(buf.as_slice(), 0, buf.len(), b"meta") {
+        Some(h) => h,
+        None => return Err(IsobmffError::NoSpatialExtents)
+    };
+    // the `meta` box has a 4-byte version/flags field before its children
+    let meta_children_start = meta.payload_start + 4;
+
+    let iprp = match find_child_box(buf.as_slice(), meta_children_start, meta.end, b"iprp") {
+        Some(h) => h,
+        None => return Err(IsobmffError::NoSpatialExtents)
+    };
+
+    let ipco = match find_child_box(buf.as_slice(), iprp.payload_start, iprp.end, b"ipco") {
+        Some(h) => h,
+        None => return Err(IsobmffError::NoSpatialExtents)
+    };
+    let ipco_children = child_boxes(buf.as_slice(), ipco.payload_start, ipco.end);
+
+    // prefer the ispe associated with the primary item via pitm/ipma
+    if let Some(item_id) = primary_item_id(buf.as_slice(), meta_children_start, meta.end) {
+        for index in item_property_indices(buf.as_slice(), iprp.payload_start, iprp.end, item_id) {
+            if index >= 1 && (index as usize) <= ipco_children.len() {
+                let candidate = &ipco_children[index as usize - 1];
+                if &candidate.box_type == b"ispe" {
+                    if let Some(dims) = ispe_dimensions(buf.as_slice(), candidate) {
+                        return Ok(dims);
+                    }
+                }
+            }
+        }
+    }
+
+    // no pitm/ipma association resolved - fall back to the first ispe found
+    match ipco_children.iter().find(|b| &b.box_type == b"ispe") {
+        Some(ispe) => ispe_dimensions(buf.as_slice(), ispe).ok_or(IsobmffError::NoSpatialExtents),
+        None => Err(IsobmffError::NoSpatialExtents)
+    }
+}
@@ -9,6 +9,14 @@ extern crate image;
 extern crate getopts;
 extern crate "rustc-serialize" as rustc_serialize;
 
+mod exif;
+mod webmercator;
+mod tiles;
+mod crs;
+mod isobmff;
+mod geojson;
+mod geotiff;
+
 use std::old_io::fs::{PathExtensions, readdir, File};
 use std::old_io::{BufferedReader, IoError};
 use std::ascii::OwnedAsciiExt;
@@ -23,17 +31,25 @@ use rustc_serialize::json;
 use image::{GenericImage, ImageDecoder, ImageError};
 use image::jpeg::JPEGDecoder;
 
+use exif::GpsInfo;
+use webmercator::{lonlat_to_merc};
+use crs::Crs;
+
+/// default ground-sample-distance (map units per pixel) used to size the
+/// footprint of a GPS-referenced image when no better estimate is known.
+static DEFAULT_GSD: f64 = 1.0;
 
-static CRS_BBOX: &'static [f64] = &[-20026376.39, -20048966.10, 20026376.39, 20048966.10];
-static CRS_WKT: &'static str = include_str!("3857.esriwkt");
 static README_TEXT: &'static str = include_str!("../README");
 static SUPPORTED_FORMAT_EXTS: &'static [&'static str] = &[
-    "jpg", 
-    "jpeg", 
-    "png", 
-    "gif", 
-    "tiff", 
-    "tif"
+    "jpg",
+    "jpeg",
+    "png",
+    "gif",
+    "tiff",
+    "tif",
+    "heic",
+    "heif",
+    "avif"
 ];
 
 
@@ -41,7 +57,9 @@ static SUPPORTED_FORMAT_EXTS: &'static [&'static str] = &[
 enum GeoRefError {
     Io(IoError),
     Image(ImageError),
-    FromUtf8Error(FromUtf8Error)
+    FromUtf8Error(FromUtf8Error),
+    Tile(tiles::TileError),
+    GeoTiff(geotiff::GeoTiffError)
 }
 
 impl FromError<IoError> for GeoRefError {
@@ -62,6 +80,18 @@ impl FromError<FromUtf8Error> for GeoRefError {
     }
 }
 
+impl FromError<tiles::TileError> for GeoRefError {
+    fn from_error(err: tiles::TileError) -> GeoRefError {
+        GeoRefError::Tile(err)
+    }
+}
+
+impl FromError<geotiff::GeoTiffError> for GeoRefError {
+    fn from_error(err: geotiff::GeoTiffError) -> GeoRefError {
+        GeoRefError::GeoTiff(err)
+    }
+}
+
 
 /// absolute difference between two values
 macro_rules! difference {
@@ -103,11 +133,12 @@ struct RefBox {
 
 impl RefBox {
 
-    fn new(width: u32, height: u32) -> RefBox {
+    fn new(width: u32, height: u32, crs: &Crs) -> RefBox {
         let raster_size = RasterSize { width: width, height: height };
-        
-        let extent_world = [difference!(CRS_BBOX[0], CRS_BBOX[2]),
-                            difference!(CRS_BBOX[1], CRS_BBOX[3])];
+        let crs_bbox = crs.bbox;
+
+        let extent_world = [difference!(crs_bbox[0], crs_bbox[2]),
+                            difference!(crs_bbox[1], crs_bbox[3])];
         let ratio_world = extent_world[0] / extent_world[1];
         let ratio_img = raster_size.width as f64 / raster_size.height as f64;
 
@@ -119,9 +150,9 @@ impl RefBox {
         }
 
         let center_world = [
-            partial_min(CRS_BBOX[0], CRS_BBOX[2]).expect("no min")
+            partial_min(crs_bbox[0], crs_bbox[2]).expect("no min")
                     + ( extent_world[0] / 2.0),
-            partial_min(CRS_BBOX[1], CRS_BBOX[3]).expect("no min")
+            partial_min(crs_bbox[1], crs_bbox[3]).expect("no min")
                     + ( extent_world[1] / 2.0)
         ];
 
@@ -138,6 +169,29 @@ impl RefBox {
         }
     }
 
+    /// place the image footprint at its true GPS location, sized by a
+    /// ground-sample-distance in map units/pixel rather than stretched to
+    /// cover the whole world.
+    fn new_from_gps(width: u32, height: u32, gps: &GpsInfo, gsd: f64) -> RefBox {
+        let raster_size = RasterSize { width: width, height: height };
+        let (center_x, center_y) = lonlat_to_merc(gps.lon, gps.lat);
+
+        let half_width = (raster_size.width as f64 * gsd) / 2.0;
+        let half_height = (raster_size.height as f64 * gsd) / 2.0;
+
+        RefBox {
+            size: raster_size,
+            bbox: BoundingBox {
+                minx: center_x - half_width,
+                miny: center_y - half_height,
+                maxx: center_x + half_width,
+                maxy: center_y + half_height
+            },
+            name: None,
+            filename: None
+        }
+    }
+
     fn world_file_values(&self) -> [f64; 6] {
         [
             //  pixel size in the x-direction in map units/pixel
@@ -179,12 +233,19 @@ fn is_supported_extension(ext: Option<&str>) -> bool {
 
 
 fn read_image_size(imagepath: &Path) -> Result<(u32, u32), GeoRefError> {
+    // optimized code path for HEIC/HEIF/AVIF - walk the box tree instead
+    // of decoding pixels
+    match isobmff::read_dimensions(imagepath) {
+        Ok(dims) => return Ok(dims),
+        Err(_) => {} // ignore, fall through to the other code paths
+    }
+
     let reader = BufferedReader::new(File::open(imagepath));
 
     // optimized code path for JPEGs - attempt to read jpeg headers
     let mut jpegdecoder = JPEGDecoder::new(reader);
     match jpegdecoder.dimensions() {
-        Ok(dims) => return Ok(dims), 
+        Ok(dims) => return Ok(dims),
         Err(_) => {} // ignore
     }
 
@@ -195,11 +256,25 @@ fn read_image_size(imagepath: &Path) -> Result<(u32, u32), GeoRefError> {
     Ok(img.dimensions())
 }
 
-fn pseudo_georef(imagepath: &Path) -> Result<RefBox, GeoRefError> {
+fn pseudo_georef(imagepath: &Path, gsd: f64, crs: &Crs, want_geotiff: bool) -> Result<RefBox, GeoRefError> {
     println!("pseudo-georeferencing {}", imagepath.as_str().unwrap_or("?"));
 
     let (width, height) = try!(read_image_size(imagepath));
-    let mut refbox = RefBox::new(width, height);
+
+    let mut refbox = match exif::read_gps(imagepath) {
+        Ok(gps) if crs.is_web_mercator() => {
+            println!("  found GPS position: {}, {}", gps.lat, gps.lon);
+            RefBox::new_from_gps(width, height, &gps, gsd)
+        },
+        Ok(_) => {
+            // GPS placement projects into EPSG:3857 meters - tagging that
+            // footprint with a different CRS would be silently wrong, so
+            // fall back to the pseudo-box instead.
+            println!("  found GPS position, but --crs is not EPSG:3857 - pseudo-georeferencing instead");
+            RefBox::new(width, height, crs)
+        },
+        Err(_) => RefBox::new(width, height, crs)
+    };
 
     let stem_res = imagepath.filestem_str();
     if stem_res.is_some() {
@@ -207,20 +282,50 @@ fn pseudo_georef(imagepath: &Path) -> Result<RefBox, GeoRefError> {
     }
     refbox.filename = Some(try!(String::from_utf8(imagepath.clone().into_vec())));
 
-    // generate world file. See: http://en.wikipedia.org/wiki/World_file
-    let mut wld_file = try!(File::create(&imagepath.with_extension("wld")));
-    for n in refbox.world_file_values().iter() {
-        try!(wld_file.write_fmt(format_args!("{}\n", n)));
-    }
+    if want_geotiff {
+        // embed the georeferencing directly in a GeoTIFF instead of
+        // sidecar .wld/.prj files
+        let world_file_values = refbox.world_file_values();
+        let img = try!(image::open(imagepath));
+        try!(geotiff::write_geotiff(&img, world_file_values[0], world_file_values[3],
+            refbox.bbox.minx, refbox.bbox.maxy, crs,
+            &imagepath.with_extension("tiff")));
+    } else {
+        // generate world file. See: http://en.wikipedia.org/wiki/World_file
+        let mut wld_file = try!(File::create(&imagepath.with_extension("wld")));
+        for n in refbox.world_file_values().iter() {
+            try!(wld_file.write_fmt(format_args!("{}\n", n)));
+        }
 
-    // generate projection file
-    let mut proj_file = try!(File::create(&imagepath.with_extension("prj")));
-    try!(proj_file.write_str(CRS_WKT));
+        // generate projection file
+        let mut proj_file = try!(File::create(&imagepath.with_extension("prj")));
+        try!(proj_file.write_str(crs.wkt.as_slice()));
+    }
 
     Ok(refbox)
 }
 
 
+/// compute the XYZ tile coverage of `refbox` across `minzoom..=maxzoom`,
+/// write it as `<image>.tiles.txt` and cut the decoded raster into
+/// `<image>_tiles/z/x/y.png`.
+fn emit_tiles(imagepath: &Path, refbox: &RefBox, minzoom: u32, maxzoom: u32) -> Result<(), GeoRefError> {
+    let tile_list = tiles::tiles_for_merc_bbox(
+        refbox.bbox.minx, refbox.bbox.miny, refbox.bbox.maxx, refbox.bbox.maxy,
+        minzoom, maxzoom, false);
+
+    println!("  covering {} tiles from zoom {} to {}", tile_list.len(), minzoom, maxzoom);
+
+    try!(tiles::write_tile_list(tile_list.as_slice(), &imagepath.with_extension("tiles.txt")));
+
+    let img = try!(image::open(imagepath));
+    let tiles_dir = Path::new(format!("{}_tiles", imagepath.as_str().unwrap_or("image")));
+    try!(tiles::cut_tiles(&img, refbox.bbox.minx, refbox.bbox.miny, refbox.bbox.maxx, refbox.bbox.maxy,
+        tile_list.as_slice(), &tiles_dir));
+
+    Ok(())
+}
+
 fn print_usage(progname: &str, opts: getopts::Options) {
     let brief = format!("Usage:\n{} [options] DIRECTORY ...", progname);
     print!("{}\n{}\n", opts.usage(brief.as_slice()), README_TEXT);
@@ -232,6 +337,13 @@ fn main() {
 
     let mut opts = getopts::Options::new();
     opts.optopt("j", "json", "Write a JSON file with boundingboxes and sizes of the images", "JSON");
+    opts.optopt("", "gsd", "Ground-sample-distance in map units/pixel used to size GPS-referenced images (default: 1.0)", "GSD");
+    opts.optopt("", "tiles", "Write XYZ tile coverage and cut the image into tiles across MINZOOM-MAXZOOM", "MINZOOM-MAXZOOM");
+    opts.optopt("", "crs", "EPSG code of the target CRS (default: 3857)", "EPSG");
+    opts.optopt("", "crs-wkt", "Path to a .prj file with a custom CRS WKT definition", "FILE");
+    opts.optopt("", "bbox", "World bounding box of the target CRS as minx,miny,maxx,maxy", "BBOX");
+    opts.optopt("", "geojson", "Write a GeoJSON FeatureCollection of the image footprints", "GEOJSON");
+    opts.optflag("", "geotiff", "Embed georeferencing as GeoTIFF tags instead of .wld/.prj sidecar files");
     opts.optflag("h", "help", "Print this help");
     let optmatches = match opts.parse(args.tail()) {
         Ok(m)   => m,
@@ -250,6 +362,56 @@ fn main() {
 
     println!("Running {} ...", progname);
 
+    let gsd = match optmatches.opt_str("gsd") {
+        Some(s) => s.parse().ok().expect("Could not parse --gsd as a number"),
+        None => DEFAULT_GSD
+    };
+
+    let zoom_range = match optmatches.opt_str("tiles") {
+        Some(s) => Some(tiles::parse_zoom_range(s.as_slice()).expect("Could not parse --tiles as MINZOOM-MAXZOOM")),
+        None => None
+    };
+
+    let bbox_override = match optmatches.opt_str("bbox") {
+        Some(s) => Some(crs::parse_bbox(s.as_slice()).expect("Could not parse --bbox as minx,miny,maxx,maxy")),
+        None => None
+    };
+
+    let epsg = match optmatches.opt_str("crs") {
+        Some(s) => Some(s.parse().ok().expect("Could not parse --crs as an EPSG code")),
+        None => None
+    };
+
+    // geographic CRSes take their world bbox directly in degrees - validate
+    // it as such rather than silently accepting out-of-range meters.
+    if epsg == Some(4326) {
+        if let Some(bbox) = bbox_override {
+            crs::Coord::new(bbox[1], bbox[0]);
+            crs::Coord::new(bbox[3], bbox[2]);
+        }
+    }
+
+    let mut target_crs = match optmatches.opt_str("crs-wkt") {
+        Some(path) => {
+            let bbox = bbox_override.expect("--crs-wkt requires --bbox to be given as well");
+            Crs::from_wkt_file(&Path::new(path), bbox).ok().expect("Could not read --crs-wkt file")
+        },
+        None => Crs::from_epsg(epsg.unwrap_or(3857)).expect("Unsupported --crs EPSG code")
+    };
+    if let Some(bbox) = bbox_override {
+        target_crs = target_crs.with_bbox(bbox);
+    }
+
+    // tiles.rs and geojson.rs both assume the RefBox bbox is EPSG:3857
+    // meters and invert the Mercator projection unconditionally - reject
+    // the combination rather than silently emitting garbage coordinates.
+    if zoom_range.is_some() && !target_crs.is_web_mercator() {
+        panic!("--tiles requires the target CRS to be EPSG:3857 (Web Mercator)");
+    }
+    if optmatches.opt_present("geojson") && !target_crs.is_web_mercator() {
+        panic!("--geojson requires the target CRS to be EPSG:3857 (Web Mercator)");
+    }
+
     for dir in optmatches.free.iter() {
         let path = Path::new(dir);
         if !path.is_dir() {
@@ -264,8 +426,13 @@ fn main() {
 
         let mut refboxes: Vec<RefBox> = vec!();
         for entity in entites.iter().filter(|&x| is_supported_extension(x.extension_str())) {
-            match pseudo_georef(entity) {
+            match pseudo_georef(entity, gsd, &target_crs, optmatches.opt_present("geotiff")) {
                 Ok(refbox) => {
+                    if let Some((minzoom, maxzoom)) = zoom_range {
+                        if let Err(e) = emit_tiles(entity, &refbox, minzoom, maxzoom) {
+                            panic!("{:?}", e);
+                        }
+                    }
                     refboxes.push(refbox);
                 },
                 Err(e) => {
@@ -291,5 +458,29 @@ fn main() {
             };
 
         }
+
+        if optmatches.opt_present("geojson") {
+            let geojson_path = Path::new(
+                    optmatches.opt_str("geojson").expect("Missing path of GeoJSON file.")
+                );
+            let footprints: Vec<geojson::Footprint> = refboxes.iter().map(|refbox| {
+                geojson::Footprint {
+                    name: refbox.name.clone(),
+                    filename: refbox.filename.clone(),
+                    width: refbox.size.width,
+                    height: refbox.size.height,
+                    minx: refbox.bbox.minx,
+                    miny: refbox.bbox.miny,
+                    maxx: refbox.bbox.maxx,
+                    maxy: refbox.bbox.maxy
+                }
+            }).collect();
+
+            let mut geojson_file = File::create(&geojson_path).unwrap();
+            let gw_res = geojson_file.write_str(geojson::to_feature_collection(footprints.as_slice()).as_slice());
+            if gw_res.is_err() {
+                panic!("Could not write to geojson file: {:?}", gw_res.err());
+            };
+        }
     }
 }
@@ -0,0 +1,202 @@
+//! XYZ/TMS slippy-map tile coverage for a georeferenced image: which
+//! tiles overlap a given EPSG:3857 bounding box, and optionally cutting
+//! the decoded raster into PNG tiles under `z/x/y.png`.
+
+use std::num::Float;
+use std::old_io::fs::{self, File};
+use std::old_io::IoError;
+
+use image::{DynamicImage, GenericImage, SubImage, ImageError};
+
+use webmercator::merc_to_lonlat;
+
+pub const TILE_SIZE: u32 = 256;
+
+#[derive(Debug)]
+pub enum TileError {
+    Io(IoError),
+    Image(ImageError)
+}
+
+impl ::std::error::FromError<IoError> for TileError {
+    fn from_error(err: IoError) -> TileError {
+        TileError::Io(err)
+    }
+}
+
+impl ::std::error::FromError<ImageError> for TileError {
+    fn from_error(err: ImageError) -> TileError {
+        TileError::Image(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tile {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32
+}
+
+/// parse a `minzoom-maxzoom` CLI argument, e.g. `"10-14"`.
+pub fn parse_zoom_range(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.splitn(2, '-');
+    let minzoom = match parts.next() { Some(p) => p.parse().ok(), None => None };
+    let maxzoom = match parts.next() { Some(p) => p.parse().ok(), None => None };
+    match (minzoom, maxzoom) {
+        (Some(a), Some(b)) => Some((a, b)),
+        _ => None
+    }
+}
+
+/// tile indices covering a lon/lat point at the given zoom level, per the
+/// standard slippy-map tile math.
+pub fn lonlat_to_tile(lon: f64, lat: f64, zoom: u32) -> (u32, u32) {
+    let n = 2.0f64.powi(zoom as i32);
+    let lat_rad = lat.to_radians();
+
+    let xtile = ((lon + 180.0) / 360.0 * n).floor();
+    let ytile = ((1.0 - (lat_rad.tan() + 1.0 / lat_rad.cos()).ln() / ::std::f64::consts::PI) / 2.0 * n).floor();
+
+    let clamp = |v: f64| -> u32 {
+        if v < 0.0 { 0 } else if v >= n { (n - 1.0) as u32 } else { v as u32 }
+    };
+
+    (clamp(xtile), clamp(ytile))
+}
+
+/// all tiles between `minzoom` and `maxzoom` (inclusive) covering an
+/// EPSG:3857 bounding box. `tms` flips the y axis (`y_tms = n - 1 - y`)
+/// for consumers expecting TMS rather than XYZ tile numbering.
+pub fn tiles_for_merc_bbox(minx: f64, miny: f64, maxx: f64, maxy: f64,
+                           minzoom: u32, maxzoom: u32, tms: bool) -> Vec<Tile> {
+    let mut tiles = vec!();
+
+    let (lon_min, lat_min) = merc_to_lonlat(minx, miny);
+    let (lon_max, lat_max) = merc_to_lonlat(maxx, maxy);
+
+    for z in minzoom..(maxzoom + 1) {
+        let n = 1u32 << z;
+
+        // latitude increases northward but tile y increases southward
+        let (x0, y0) = lonlat_to_tile(lon_min, lat_max, z);
+        let (x1, y1) = lonlat_to_tile(lon_max, lat_min, z);
+
+        let (xmin, xmax) = if x0 <= x1 { (x0, x1) } else { (x1, x0) };
+        let (ymin, ymax) = if y0 <= y1 { (y0, y1) } else { (y1, y0) };
+
+        for x in xmin..(xmax + 1) {
+            for y in ymin..(ymax + 1) {
+                let tile_y = if tms { n - 1 - y } else { y };
+                tiles.push(Tile { z: z, x: x, y: tile_y });
+            }
+        }
+    }
+
+    tiles
+}
+
+/// write the tile list, one `z/x/y` per line, to `outpath`.
+pub fn write_tile_list(tiles: &[Tile], outpath: &Path) -> Result<(), TileError> {
+    let mut file = try!(File::create(outpath));
+    for tile in tiles.iter() {
+        try!(file.write_fmt(format_args!("{}/{}/{}\n", tile.z, tile.x, tile.y)));
+    }
+    Ok(())
+}
+
+/// cut `img`, covering `minx,miny,maxx,maxy` in EPSG:3857, into PNG tiles
+/// under `outdir/z/x/y.png` for every tile in `tiles`. Each PNG is the part
+/// of the source raster that the tile overlaps, cropped at the source
+/// resolution and to the image's own extent; it is not resampled to
+/// 256x256 pixels.
+pub fn cut_tiles(img: &DynamicImage, minx: f64, miny: f64, maxx: f64, maxy: f64,
+                  tiles: &[Tile], outdir: &Path) -> Result<(), TileError> {
+    let (width, height) = img.dimensions();
+
+    for tile in tiles.iter() {
+        // map the tile's lon/lat extent back onto pixel space of the source image
+        let (tile_lon_min, tile_lat_max) = tile_xy_to_lonlat(tile.x, tile.y, tile.z);
+        let (tile_lon_max, tile_lat_min) = tile_xy_to_lonlat(tile.x + 1, tile.y + 1, tile.z);
+
+        let (tminx, tminy) = ::webmercator::lonlat_to_merc(tile_lon_min, tile_lat_min);
+        let (tmaxx, tmaxy) = ::webmercator::lonlat_to_merc(tile_lon_max, tile_lat_max);
+
+        let px_min_x = ((tminx - minx) / (maxx - minx) * width as f64).max(0.0) as u32;
+        let px_max_x = ((tmaxx - minx) / (maxx - minx) * width as f64).min(width as f64) as u32;
+        let px_min_y = ((maxy - tmaxy) / (maxy - miny) * height as f64).max(0.0) as u32;
+        let px_max_y = ((maxy - tminy) / (maxy - miny) * height as f64).min(height as f64) as u32;
+
+        if px_max_x <= px_min_x || px_max_y <= px_min_y {
+            continue; // tile does not overlap the image extent
+        }
+
+        let tile_dir = outdir.join(format!("{}", tile.z)).join(format!("{}", tile.x));
+        try!(fs::mkdir_recursive(&tile_dir, ::std::old_io::USER_RWX));
+
+        let cropped: SubImage<&DynamicImage> = img.sub_image(
+            px_min_x, px_min_y, px_max_x - px_min_x, px_max_y - px_min_y);
+        let tile_path = tile_dir.join(format!("{}.png", tile.y));
+        try!(cropped.to_image().save(&tile_path));
+    }
+
+    Ok(())
+}
+
+fn tile_xy_to_lonlat(x: u32, y: u32, z: u32) -> (f64, f64) {
+    let n = 2.0f64.powi(z as i32);
+    let lon = (x as f64) / n * 360.0 - 180.0;
+    let lat_rad = (::std::f64::consts::PI * (1.0 - 2.0 * (y as f64) / n)).sinh().atan();
+    (lon, lat_rad.to_degrees())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lonlat_to_tile, tiles_for_merc_bbox};
+    use webmercator::lonlat_to_merc;
+
+    #[test]
+    fn lonlat_to_tile_matches_known_tile() {
+        // Berlin at zoom 10, verified against a standard slippy-map tile calculator
+        let (x, y) = lonlat_to_tile(13.405, 52.52, 10);
+        assert_eq!((x, y), (550, 335));
+    }
+
+    #[test]
+    fn lonlat_to_tile_clamps_at_the_antimeridian() {
+        let (x, _) = lonlat_to_tile(180.0, 0.0, 4);
+        assert_eq!(x, 15); // n - 1 at zoom 4 (n = 16)
+    }
+
+    #[test]
+    fn tiles_for_merc_bbox_covers_a_single_tile_at_low_zoom() {
+        // a box wholly inside the south-east quadrant, clear of the origin
+        // where all four zoom-1 tiles meet
+        let (minx, miny) = lonlat_to_merc(10.0, -20.0);
+        let (maxx, maxy) = lonlat_to_merc(20.0, -10.0);
+        let tiles = tiles_for_merc_bbox(minx, miny, maxx, maxy, 1, 1, false);
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].z, 1);
+        assert_eq!(tiles[0].x, 1);
+        assert_eq!(tiles[0].y, 1);
+    }
+
+    #[test]
+    fn tiles_for_merc_bbox_tms_flips_y() {
+        let (minx, miny) = lonlat_to_merc(-10.0, -10.0);
+        let (maxx, maxy) = lonlat_to_merc(10.0, 10.0);
+        let xyz = tiles_for_merc_bbox(minx, miny, maxx, maxy, 1, 1, false);
+        let tms = tiles_for_merc_bbox(minx, miny, maxx, maxy, 1, 1, true);
+        assert_eq!(tms[0].y, 2 - 1 - xyz[0].y);
+    }
+
+    #[test]
+    fn tiles_for_merc_bbox_spans_the_requested_zoom_range() {
+        let (minx, miny) = lonlat_to_merc(-1.0, -1.0);
+        let (maxx, maxy) = lonlat_to_merc(1.0, 1.0);
+        let tiles = tiles_for_merc_bbox(minx, miny, maxx, maxy, 2, 4, false);
+        let zooms: Vec<u32> = tiles.iter().map(|t| t.z).collect();
+        assert!(zooms.contains(&2));
+        assert!(zooms.contains(&3));
+        assert!(zooms.contains(&4));
+    }
+}
@@ -0,0 +1,65 @@
+//! EPSG:3857 (Web Mercator) forward/inverse projection helpers shared by
+//! the GPS georeferencing, tile cutting and GeoJSON export code paths.
+
+use std::num::Float;
+
+/// WGS84 semi-major axis, as used by the spherical Web Mercator projection.
+pub const EARTH_RADIUS: f64 = 6378137.0;
+
+/// latitudes beyond this clamp project to +/- infinity in Web Mercator.
+pub const MAX_LATITUDE: f64 = 85.05112878;
+
+/// project a WGS84 lon/lat (degrees) to EPSG:3857 meters.
+pub fn lonlat_to_merc(lon: f64, lat: f64) -> (f64, f64) {
+    let clamped_lat = lat.max(-MAX_LATITUDE).min(MAX_LATITUDE);
+    let x = EARTH_RADIUS * lon.to_radians();
+    let y = EARTH_RADIUS * (::std::f64::consts::PI / 4.0 + clamped_lat.to_radians() / 2.0).tan().ln();
+    (x, y)
+}
+
+/// inverse of `lonlat_to_merc`: EPSG:3857 meters back to WGS84 lon/lat (degrees).
+pub fn merc_to_lonlat(x: f64, y: f64) -> (f64, f64) {
+    let lon = (x / EARTH_RADIUS).to_degrees();
+    let lat = (2.0 * (y / EARTH_RADIUS).exp().atan() - ::std::f64::consts::PI / 2.0).to_degrees();
+    (lon, lat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lonlat_to_merc, merc_to_lonlat, MAX_LATITUDE};
+
+    fn assert_close(a: f64, b: f64, epsilon: f64) {
+        assert!((a - b).abs() < epsilon, "{} not within {} of {}", a, epsilon, b);
+    }
+
+    #[test]
+    fn origin_projects_to_origin() {
+        let (x, y) = lonlat_to_merc(0.0, 0.0);
+        assert_close(x, 0.0, 1e-6);
+        assert_close(y, 0.0, 1e-6);
+    }
+
+    #[test]
+    fn known_reference_point() {
+        // Greenwich Observatory, verified against a standard Web Mercator calculator
+        let (x, y) = lonlat_to_merc(-0.0014, 51.4779);
+        assert_close(x, -155.87, 0.1);
+        assert_close(y, 6706268.07, 1.0);
+    }
+
+    #[test]
+    fn forward_and_inverse_round_trip() {
+        let (lon, lat) = (13.405, 52.52); // Berlin
+        let (x, y) = lonlat_to_merc(lon, lat);
+        let (lon2, lat2) = merc_to_lonlat(x, y);
+        assert_close(lon, lon2, 1e-9);
+        assert_close(lat, lat2, 1e-9);
+    }
+
+    #[test]
+    fn latitude_is_clamped_before_projecting() {
+        let (_, y_at_pole) = lonlat_to_merc(0.0, 90.0);
+        let (_, y_at_clamp) = lonlat_to_merc(0.0, MAX_LATITUDE);
+        assert_close(y_at_pole, y_at_clamp, 1e-6);
+    }
+}